@@ -3,93 +3,105 @@
 //! A high-performance HTTP service for estimating token counts and costs
 //! for LLM API calls. Designed to offload tokenization from Python workers.
 //!
-//! Token estimation uses a character-based heuristic calibrated against
-//! OpenAI's tiktoken for English text. This is an approximation—actual
-//! token counts may vary by ±10% depending on content.
+//! Token estimation defaults to a character-based heuristic calibrated
+//! against OpenAI's tiktoken for English text (±10% off). Callers that need
+//! billing-accurate counts can opt into the exact tokenizer backend; see
+//! `tokenizer` for details.
+
+mod metrics;
+mod pricing;
+mod tokenizer;
+mod usage;
+mod validation;
 
 use axum::{
-    extract::Json,
-    http::StatusCode,
+    extract::{Json, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
     routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{watch, Semaphore};
 use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
-/// Model pricing configuration (USD per 1K tokens)
-/// Updated: January 2024
-struct ModelPricing {
-    input: f64,
-    output: f64,
+use metrics::Metrics;
+use pricing::PricingSnapshot;
+use tokenizer::TokenizerBackend;
+use usage::UsageStore;
+
+/// Shared state handed to every handler via axum's `State` extractor.
+#[derive(Debug, Clone)]
+struct AppState {
+    usage: UsageStore,
+    pricing: watch::Receiver<PricingSnapshot>,
+    metrics: Metrics,
+    /// `true` once the exact tokenizer's encodings have finished loading.
+    exact_tokenizer_ready: watch::Receiver<bool>,
 }
 
-fn get_model_pricing() -> HashMap<&'static str, ModelPricing> {
-    let mut pricing = HashMap::new();
-
-    // GPT-3.5 Turbo
-    pricing.insert("gpt-3.5-turbo", ModelPricing { input: 0.0005, output: 0.0015 });
-    pricing.insert("gpt-3.5-turbo-0125", ModelPricing { input: 0.0005, output: 0.0015 });
-    pricing.insert("gpt-3.5-turbo-1106", ModelPricing { input: 0.001, output: 0.002 });
-
-    // GPT-4
-    pricing.insert("gpt-4", ModelPricing { input: 0.03, output: 0.06 });
-    pricing.insert("gpt-4-0613", ModelPricing { input: 0.03, output: 0.06 });
-
-    // GPT-4 Turbo
-    pricing.insert("gpt-4-turbo", ModelPricing { input: 0.01, output: 0.03 });
-    pricing.insert("gpt-4-turbo-preview", ModelPricing { input: 0.01, output: 0.03 });
-    pricing.insert("gpt-4-1106-preview", ModelPricing { input: 0.01, output: 0.03 });
-
-    // GPT-4o
-    pricing.insert("gpt-4o", ModelPricing { input: 0.005, output: 0.015 });
-    pricing.insert("gpt-4o-mini", ModelPricing { input: 0.00015, output: 0.0006 });
-
-    // Claude models (for future provider support)
-    pricing.insert("claude-3-opus", ModelPricing { input: 0.015, output: 0.075 });
-    pricing.insert("claude-3-sonnet", ModelPricing { input: 0.003, output: 0.015 });
-    pricing.insert("claude-3-haiku", ModelPricing { input: 0.00025, output: 0.00125 });
+/// Read the caller's API key from the `X-Api-Key` header, falling back to an
+/// `api_key` field in the request body.
+fn extract_api_key(headers: &HeaderMap, body_key: Option<&str>) -> Option<String> {
+    headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| body_key.map(str::to_string))
+}
 
-    pricing
+/// Estimate token count for `text` against `model` using the given backend,
+/// returning the count and the backend that actually produced it (an exact
+/// request silently falls back to the heuristic when no encoding matches).
+fn estimate_tokens(text: &str, model: &str, backend: TokenizerBackend) -> (u32, TokenizerBackend) {
+    let tok = tokenizer::resolve(model, backend);
+    (tok.count(text), tok.backend())
 }
 
-/// Estimate token count from text using character-based heuristic.
-///
-/// Calibration methodology:
-/// - English text averages ~4 characters per token with GPT tokenizers
-/// - Whitespace and punctuation count as partial tokens
-/// - This approximation is intentionally conservative (slightly over-estimates)
-///
-/// For production use with strict accuracy requirements, integrate tiktoken
-/// via PyO3 bindings or use the tiktoken-rs crate.
-fn estimate_tokens(text: &str) -> u32 {
-    if text.is_empty() {
-        return 0;
+/// Count tokens for a list of chat messages the way tiktoken's
+/// `num_tokens_from_messages` does for cl100k_base models: each message costs
+/// `3 + tokens(content) + tokens(role)` (plus `1` if it has a `name`), and the
+/// whole conversation pays a flat `3` for the assistant reply priming.
+fn estimate_message_tokens(
+    messages: &[ChatMessage],
+    model: &str,
+    backend: TokenizerBackend,
+) -> (u32, TokenizerBackend) {
+    let tok = tokenizer::resolve(model, backend);
+
+    let mut total = 0u32;
+    for message in messages {
+        total += 3 + tok.count(&message.content) + tok.count(&message.role);
+        if message.name.is_some() {
+            total += 1;
+        }
     }
+    total += 3; // every reply is primed with <|start|>assistant<|message|>
 
-    // Character-based estimation: ~4 chars per token for English
-    // Add small buffer for special tokens and edge cases
-    let char_count = text.chars().count();
-    let base_estimate = (char_count as f64 / 4.0).ceil() as u32;
-
-    // Account for whitespace density (more spaces = slightly more tokens)
-    let whitespace_count = text.chars().filter(|c| c.is_whitespace()).count();
-    let whitespace_factor = 1.0 + (whitespace_count as f64 / char_count as f64) * 0.1;
-
-    ((base_estimate as f64) * whitespace_factor).ceil() as u32
+    (total, tok.backend())
 }
 
-/// Calculate cost based on token counts and model pricing
-fn calculate_cost(model: &str, tokens_in: u32, tokens_out: u32) -> f64 {
-    let pricing = get_model_pricing();
-
-    let model_pricing = pricing.get(model).unwrap_or_else(|| {
-        // Default to GPT-3.5 pricing for unknown models
-        pricing.get("gpt-3.5-turbo").unwrap()
-    });
+/// Calculate cost based on token counts and the current pricing table.
+///
+/// Unknown models default to GPT-3.5 pricing; if the operator-supplied
+/// `PRICING_FILE` doesn't carry that key either, we have no rate to charge
+/// and return a zero cost rather than panicking on valid configuration.
+fn calculate_cost(
+    pricing: &HashMap<String, pricing::PricingRecord>,
+    model: &str,
+    tokens_in: u32,
+    tokens_out: u32,
+) -> f64 {
+    let Some(model_pricing) = pricing.get(model).or_else(|| pricing.get("gpt-3.5-turbo")) else {
+        warn!(model, "no pricing entry for model or gpt-3.5-turbo fallback; charging zero");
+        return 0.0;
+    };
 
     let input_cost = (tokens_in as f64 / 1000.0) * model_pricing.input;
     let output_cost = (tokens_out as f64 / 1000.0) * model_pricing.output;
@@ -98,31 +110,86 @@ fn calculate_cost(model: &str, tokens_in: u32, tokens_out: u32) -> f64 {
     ((input_cost + output_cost) * 100_000_000.0).round() / 100_000_000.0
 }
 
+/// Cap the Prometheus `model` label to names the pricing table actually
+/// knows about, collapsing everything else into `other`. Without this, a
+/// client sending many distinct (or garbage) `model` values would grow the
+/// metrics registry's label cardinality without bound.
+fn metric_model_label<'a>(
+    model: &'a str,
+    pricing: &HashMap<String, pricing::PricingRecord>,
+) -> &'a str {
+    if pricing.contains_key(model) {
+        model
+    } else {
+        "other"
+    }
+}
+
 // === Request/Response Types ===
 
 #[derive(Debug, Deserialize)]
 struct TokenEstimateRequest {
-    /// Input text to tokenize
-    text: String,
+    /// Input text to tokenize. Ignored when a non-empty `messages` array is
+    /// also given.
+    #[serde(default)]
+    text: Option<String>,
+    /// A chat-completion style message array to tokenize instead of a flat
+    /// `text` string, including the per-message overhead tiktoken charges.
+    #[serde(default)]
+    messages: Option<Vec<ChatMessage>>,
     /// Model name for pricing lookup
     #[serde(default = "default_model")]
     model: String,
+    /// API key to attribute usage to, as an alternative to the
+    /// `X-Api-Key` header
+    #[serde(default)]
+    api_key: Option<String>,
+    /// If `text` exceeds `MAX_INPUT_CHARS`/`MAX_INPUT_TOKENS`, count only the
+    /// truncated prefix instead of rejecting the request with `413`
+    #[serde(default)]
+    truncate: bool,
 }
 
 fn default_model() -> String {
     "gpt-3.5-turbo".to_string()
 }
 
+/// A single OpenAI-style chat message, as sent to `/v1/chat/completions`.
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 struct TokenEstimateResponse {
     tokens: u32,
     model: String,
+    /// Whether `tokens` is an exact count or a heuristic approximation.
+    backend: TokenizerBackend,
+    /// Whether `text` was shortened to fit `MAX_INPUT_CHARS`/`MAX_INPUT_TOKENS`.
+    truncated: bool,
+    /// Characters dropped from the end of `text` to make it fit.
+    chars_dropped: usize,
+}
+
+/// Query parameters accepted on `/tokens`, e.g. `/tokens?backend=exact`.
+#[derive(Debug, Deserialize)]
+struct TokensQuery {
+    #[serde(default)]
+    backend: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct CostEstimateRequest {
     /// Input text (will be tokenized)
     input_text: Option<String>,
+    /// Input chat messages (will be tokenized with per-message overhead),
+    /// as an alternative to `input_text`
+    #[serde(default)]
+    messages: Option<Vec<ChatMessage>>,
     /// Pre-counted input tokens (if already known)
     tokens_in: Option<u32>,
     /// Output text (will be tokenized)
@@ -132,6 +199,18 @@ struct CostEstimateRequest {
     /// Model name for pricing lookup
     #[serde(default = "default_model")]
     model: String,
+    /// API key to attribute usage to, as an alternative to the
+    /// `X-Api-Key` header
+    #[serde(default)]
+    api_key: Option<String>,
+    /// If `input_text` exceeds `MAX_INPUT_CHARS`/`MAX_INPUT_TOKENS`, price
+    /// the truncated prefix instead of rejecting the request with `413`
+    #[serde(default)]
+    truncate: bool,
+    /// Only report whether the prompt fits the model's context window;
+    /// skip computing cost entirely
+    #[serde(default)]
+    validate_only: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -140,6 +219,13 @@ struct CostEstimateResponse {
     tokens_out: u32,
     cost_usd: f64,
     model: String,
+    /// Whether `input_text` was shortened to fit `MAX_INPUT_CHARS`/`MAX_INPUT_TOKENS`.
+    truncated: bool,
+    /// Characters dropped from the end of `input_text` to make it fit.
+    chars_dropped: usize,
+    /// Set when `validate_only` was requested and the model has a known
+    /// context window: whether `tokens_in` fits within it.
+    fits_context_window: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
@@ -152,6 +238,83 @@ struct HealthResponse {
 #[derive(Debug, Serialize)]
 struct ErrorResponse {
     error: String,
+    /// Which configured limit rejected the request, set only for `413`s
+    /// raised by the validation layer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<&'static str>,
+}
+
+impl ErrorResponse {
+    fn simple(error: impl Into<String>) -> Self {
+        Self { error: error.into(), limit: None }
+    }
+}
+
+/// Turn a rejected `validation::Violation` into a `413` response naming the
+/// limit that was exceeded.
+fn violation_response(violation: validation::Violation) -> (StatusCode, Json<ErrorResponse>) {
+    let limit = violation.limit_name();
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(ErrorResponse {
+            error: format!(
+                "input exceeds {limit} ({} > {}); set truncate=true to proceed",
+                violation.actual(),
+                violation.limit_value()
+            ),
+            limit: Some(limit),
+        }),
+    )
+}
+
+/// A single prompt to price within a `/tokens/batch` call.
+#[derive(Debug, Deserialize)]
+struct BatchTokenizeItem {
+    /// Caller-supplied identifier, echoed back so results can be matched up
+    /// regardless of completion order.
+    id: String,
+    text: String,
+    #[serde(default = "default_model")]
+    model: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchTokenizeRequest {
+    items: Vec<BatchTokenizeItem>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchTokenizeResult {
+    id: String,
+    tokens: u32,
+    cost_usd: f64,
+    model: String,
+    backend: TokenizerBackend,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchTokenizeResponse {
+    results: Vec<BatchTokenizeResult>,
+}
+
+fn max_batch_size() -> usize {
+    std::env::var("MAX_BATCH_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1000)
+}
+
+fn batch_concurrency() -> usize {
+    std::env::var("BATCH_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(16)
+}
+
+#[derive(Debug, Serialize)]
+struct ModelsResponse {
+    models: HashMap<String, pricing::PricingRecord>,
+    last_updated: u64,
 }
 
 // === Handlers ===
@@ -165,66 +328,262 @@ async fn health() -> Json<HealthResponse> {
 }
 
 async fn estimate_tokens_handler(
+    State(state): State<AppState>,
+    Query(query): Query<TokensQuery>,
+    headers: HeaderMap,
     Json(req): Json<TokenEstimateRequest>,
 ) -> Result<Json<TokenEstimateResponse>, (StatusCode, Json<ErrorResponse>)> {
-    if req.text.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "text field is required and cannot be empty".to_string(),
-            }),
-        ));
-    }
+    state.metrics.record_request("/tokens");
+    let started_at = Instant::now();
+
+    let requested_backend = query
+        .backend
+        .as_deref()
+        .map(TokenizerBackend::parse)
+        .unwrap_or_else(TokenizerBackend::from_env);
+
+    let (tokens, backend, truncated, chars_dropped) = match (&req.messages, &req.text) {
+        (Some(messages), _) if !messages.is_empty() => {
+            let (tokens, backend) =
+                estimate_message_tokens(messages, &req.model, requested_backend);
+            (tokens, backend, false, 0)
+        }
+        (_, Some(text)) if !text.is_empty() => {
+            let validated = validation::validate(
+                text,
+                validation::Limits::from_env(),
+                req.truncate,
+                &req.model,
+                requested_backend,
+            )
+            .map_err(violation_response)?;
+            let (tokens, backend) =
+                estimate_tokens(&validated.text, &req.model, requested_backend);
+            (tokens, backend, validated.truncated, validated.chars_dropped)
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::simple(
+                    "either a non-empty text field or a non-empty messages array is required",
+                )),
+            ));
+        }
+    };
+
+    state
+        .metrics
+        .observe_tokenize_duration(backend.as_str(), started_at.elapsed().as_secs_f64());
+    let pricing = state.pricing.borrow().models.clone();
+    state.metrics.record_tokens(metric_model_label(&req.model, &pricing), tokens);
 
-    let tokens = estimate_tokens(&req.text);
+    if let Some(api_key) = extract_api_key(&headers, req.api_key.as_deref()) {
+        state.usage.record(&api_key, tokens, 0, 0.0);
+    }
 
     Ok(Json(TokenEstimateResponse {
         tokens,
         model: req.model,
+        backend,
+        truncated,
+        chars_dropped,
     }))
 }
 
+/// Tokenize and price many prompts in one round trip, processing items
+/// concurrently under a semaphore so a huge batch doesn't exhaust CPU.
+async fn batch_tokenize_handler(
+    State(state): State<AppState>,
+    Json(req): Json<BatchTokenizeRequest>,
+) -> Result<Json<BatchTokenizeResponse>, (StatusCode, Json<ErrorResponse>)> {
+    state.metrics.record_request("/tokens/batch");
+    let max_items = max_batch_size();
+    if req.items.len() > max_items {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ErrorResponse::simple(format!(
+                "batch of {} items exceeds the max batch size of {max_items}",
+                req.items.len()
+            ))),
+        ));
+    }
+
+    let requested_backend = TokenizerBackend::from_env();
+    let semaphore = Arc::new(Semaphore::new(batch_concurrency()));
+    let pricing = state.pricing.borrow().models.clone();
+
+    let tasks: Vec<_> = req
+        .items
+        .into_iter()
+        .map(|item| {
+            let semaphore = Arc::clone(&semaphore);
+            let pricing = Arc::clone(&pricing);
+            let metrics = state.metrics.clone();
+            tokio::spawn(async move {
+                let _permit = semaphore
+                    .acquire_owned()
+                    .await
+                    .expect("batch semaphore was closed while a task was queued");
+                let started_at = Instant::now();
+                let (tokens, backend) =
+                    estimate_tokens(&item.text, &item.model, requested_backend);
+                metrics.observe_tokenize_duration(backend.as_str(), started_at.elapsed().as_secs_f64());
+                let model_label = metric_model_label(&item.model, &pricing);
+                metrics.record_tokens(model_label, tokens);
+                let cost_usd = calculate_cost(&pricing, &item.model, tokens, 0);
+                metrics.record_cost(model_label, cost_usd);
+                BatchTokenizeResult {
+                    id: item.id,
+                    tokens,
+                    cost_usd,
+                    model: item.model,
+                    backend,
+                }
+            })
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        results.push(task.await.expect("batch tokenize task panicked"));
+    }
+
+    Ok(Json(BatchTokenizeResponse { results }))
+}
+
 async fn estimate_cost_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(req): Json<CostEstimateRequest>,
 ) -> Result<Json<CostEstimateResponse>, (StatusCode, Json<ErrorResponse>)> {
-    // Determine input tokens
-    let tokens_in = match (req.tokens_in, &req.input_text) {
-        (Some(t), _) => t,
-        (None, Some(text)) => estimate_tokens(text),
-        (None, None) => 0,
+    state.metrics.record_request("/cost");
+    let requested_backend = TokenizerBackend::from_env();
+
+    // Determine input tokens: pre-counted > messages > input_text, validating
+    // (and possibly truncating) a raw input_text against the configured limits
+    let (tokens_in, truncated, chars_dropped) = match (req.tokens_in, &req.messages, &req.input_text) {
+        (Some(t), _, _) => (t, false, 0),
+        (None, Some(messages), _) if !messages.is_empty() => {
+            (estimate_message_tokens(messages, &req.model, requested_backend).0, false, 0)
+        }
+        (None, _, Some(text)) => {
+            let validated = validation::validate(
+                text,
+                validation::Limits::from_env(),
+                req.truncate,
+                &req.model,
+                requested_backend,
+            )
+            .map_err(violation_response)?;
+            let tokens = estimate_tokens(&validated.text, &req.model, requested_backend).0;
+            (tokens, validated.truncated, validated.chars_dropped)
+        }
+        (None, _, None) => (0, false, 0),
     };
 
     // Determine output tokens
     let tokens_out = match (req.tokens_out, &req.output_text) {
         (Some(t), _) => t,
-        (None, Some(text)) => estimate_tokens(text),
+        (None, Some(text)) => estimate_tokens(text, &req.model, requested_backend).0,
         (None, None) => 0,
     };
 
     if tokens_in == 0 && tokens_out == 0 {
         return Err((
             StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "At least one of tokens_in, input_text, tokens_out, or output_text is required".to_string(),
-            }),
+            Json(ErrorResponse::simple(
+                "At least one of tokens_in, input_text, messages, tokens_out, or output_text is required",
+            )),
         ));
     }
 
-    let cost = calculate_cost(&req.model, tokens_in, tokens_out);
+    let pricing = state.pricing.borrow().models.clone();
+
+    if req.validate_only {
+        let fits_context_window = pricing
+            .get(&req.model)
+            .and_then(|p| p.context_window)
+            .map(|window| tokens_in <= window);
+
+        return Ok(Json(CostEstimateResponse {
+            tokens_in,
+            tokens_out,
+            cost_usd: 0.0,
+            model: req.model,
+            truncated,
+            chars_dropped,
+            fits_context_window,
+        }));
+    }
+
+    let cost = calculate_cost(&pricing, &req.model, tokens_in, tokens_out);
+
+    let model_label = metric_model_label(&req.model, &pricing);
+    state.metrics.record_tokens(model_label, tokens_in + tokens_out);
+    state.metrics.record_cost(model_label, cost);
+
+    if let Some(api_key) = extract_api_key(&headers, req.api_key.as_deref()) {
+        state.usage.record(&api_key, tokens_in, tokens_out, cost);
+    }
 
     Ok(Json(CostEstimateResponse {
         tokens_in,
         tokens_out,
         cost_usd: cost,
         model: req.model,
+        truncated,
+        chars_dropped,
+        fits_context_window: None,
     }))
 }
 
-async fn list_models() -> Json<Vec<&'static str>> {
-    let pricing = get_model_pricing();
-    let mut models: Vec<&str> = pricing.keys().copied().collect();
-    models.sort();
-    Json(models)
+async fn list_models(State(state): State<AppState>) -> Json<ModelsResponse> {
+    // `serde`'s blanket `Serialize` impl for `Arc<T>` requires the `rc`
+    // feature, which this workspace doesn't enable, so the pricing map has
+    // to be cloned out of the `Arc` rather than serialized through it.
+    let snapshot = state.pricing.borrow().clone();
+    Json(ModelsResponse {
+        models: (*snapshot.models).clone(),
+        last_updated: snapshot.last_updated,
+    })
+}
+
+async fn get_usage_for_key(
+    State(state): State<AppState>,
+    Path(api_key): Path<String>,
+) -> Result<Json<usage::UsageSummary>, (StatusCode, Json<ErrorResponse>)> {
+    state.usage.get(&api_key).map(Json).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::simple(format!(
+                "no usage recorded for api key '{api_key}'"
+            ))),
+        )
+    })
+}
+
+async fn get_usage_summary(State(state): State<AppState>) -> Json<Vec<usage::UsageSummary>> {
+    Json(state.usage.all())
+}
+
+/// Prometheus scrape target.
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [("content-type", "text/plain; version=0.0.4")],
+        state.metrics.gather(),
+    )
+}
+
+/// Readiness probe distinct from `/health`: reports `200` only once the
+/// exact-tokenizer encodings have finished loading, so a load balancer can
+/// hold traffic back from a pod that would otherwise silently fall back to
+/// the heuristic backend for its first few requests.
+async fn readiness_handler(State(state): State<AppState>) -> StatusCode {
+    if *state.exact_tokenizer_ready.borrow() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
 }
 
 #[tokio::main]
@@ -242,13 +601,34 @@ async fn main() {
         .allow_methods(Any)
         .allow_headers(Any);
 
+    // Load pricing once at startup and keep it fresh in the background
+    let (pricing_tx, pricing_rx) = watch::channel(pricing::load_initial());
+    pricing::spawn_watcher(pricing_tx);
+
+    // Warm the exact tokenizer's encodings in the background; /ready flips
+    // to 200 once they're loaded.
+    let (ready_tx, ready_rx) = watch::channel(false);
+    tokenizer::spawn_encoding_loader(ready_tx);
+
     // Build router
+    let state = AppState {
+        usage: UsageStore::new(),
+        pricing: pricing_rx,
+        metrics: Metrics::new(),
+        exact_tokenizer_ready: ready_rx,
+    };
     let app = Router::new()
         .route("/health", get(health))
+        .route("/ready", get(readiness_handler))
+        .route("/metrics", get(metrics_handler))
         .route("/tokens", post(estimate_tokens_handler))
+        .route("/tokens/batch", post(batch_tokenize_handler))
         .route("/cost", post(estimate_cost_handler))
         .route("/models", get(list_models))
-        .layer(cors);
+        .route("/usage", get(get_usage_summary))
+        .route("/usage/:key", get(get_usage_for_key))
+        .layer(cors)
+        .with_state(state);
 
     // Bind to port from environment or default
     let port: u16 = std::env::var("PORT")
@@ -269,28 +649,62 @@ mod tests {
 
     #[test]
     fn test_estimate_tokens_empty() {
-        assert_eq!(estimate_tokens(""), 0);
+        let (tokens, backend) = estimate_tokens("", "gpt-3.5-turbo", TokenizerBackend::Heuristic);
+        assert_eq!(tokens, 0);
+        assert_eq!(backend, TokenizerBackend::Heuristic);
     }
 
     #[test]
-    fn test_estimate_tokens_short() {
-        // "Hello" = 5 chars, ~1-2 tokens
-        let tokens = estimate_tokens("Hello");
-        assert!(tokens >= 1 && tokens <= 3);
+    fn test_estimate_tokens_falls_back_when_no_encoding_registered() {
+        // Claude models have no tiktoken/HF encoding wired up, so an exact
+        // request should silently fall back to the heuristic.
+        let (_, backend) =
+            estimate_tokens("Hello", "claude-3-opus", TokenizerBackend::Exact);
+        assert_eq!(backend, TokenizerBackend::Heuristic);
     }
 
     #[test]
-    fn test_estimate_tokens_sentence() {
-        // "The quick brown fox jumps over the lazy dog" = 43 chars, ~9-11 tokens
-        let tokens = estimate_tokens("The quick brown fox jumps over the lazy dog");
-        assert!(tokens >= 8 && tokens <= 15);
+    fn test_estimate_message_tokens_charges_per_message_overhead() {
+        let messages = vec![
+            ChatMessage {
+                role: "system".to_string(),
+                content: "You are helpful.".to_string(),
+                name: None,
+            },
+            ChatMessage {
+                role: "user".to_string(),
+                content: "Hi".to_string(),
+                name: Some("alice".to_string()),
+            },
+        ];
+        let (flat_tokens, _) =
+            estimate_tokens("You are helpful.Hi", "gpt-3.5-turbo", TokenizerBackend::Heuristic);
+        let (message_tokens, _) =
+            estimate_message_tokens(&messages, "gpt-3.5-turbo", TokenizerBackend::Heuristic);
+
+        // Per-message overhead (3 per message + 1 for the name + 3 trailing)
+        // must always push the total above the flat concatenation.
+        assert!(message_tokens > flat_tokens);
+    }
+
+    fn test_pricing() -> HashMap<String, pricing::PricingRecord> {
+        let mut table = HashMap::new();
+        table.insert(
+            "gpt-3.5-turbo".to_string(),
+            pricing::PricingRecord { input: 0.0005, output: 0.0015, cached_input: None, context_window: Some(16_385) },
+        );
+        table.insert(
+            "gpt-4".to_string(),
+            pricing::PricingRecord { input: 0.03, output: 0.06, cached_input: None, context_window: Some(8_192) },
+        );
+        table
     }
 
     #[test]
     fn test_calculate_cost_gpt35() {
         // 1000 tokens in, 500 tokens out for gpt-3.5-turbo
         // Cost = (1000/1000 * 0.0005) + (500/1000 * 0.0015) = 0.0005 + 0.00075 = 0.00125
-        let cost = calculate_cost("gpt-3.5-turbo", 1000, 500);
+        let cost = calculate_cost(&test_pricing(), "gpt-3.5-turbo", 1000, 500);
         assert!((cost - 0.00125).abs() < 0.0001);
     }
 
@@ -298,15 +712,43 @@ mod tests {
     fn test_calculate_cost_gpt4() {
         // 1000 tokens in, 500 tokens out for gpt-4
         // Cost = (1000/1000 * 0.03) + (500/1000 * 0.06) = 0.03 + 0.03 = 0.06
-        let cost = calculate_cost("gpt-4", 1000, 500);
+        let cost = calculate_cost(&test_pricing(), "gpt-4", 1000, 500);
         assert!((cost - 0.06).abs() < 0.0001);
     }
 
     #[test]
     fn test_calculate_cost_unknown_model() {
         // Unknown model should default to gpt-3.5-turbo pricing
-        let cost = calculate_cost("unknown-model", 1000, 500);
-        let expected = calculate_cost("gpt-3.5-turbo", 1000, 500);
+        let cost = calculate_cost(&test_pricing(), "unknown-model", 1000, 500);
+        let expected = calculate_cost(&test_pricing(), "gpt-3.5-turbo", 1000, 500);
         assert_eq!(cost, expected);
     }
+
+    #[test]
+    fn test_calculate_cost_unknown_model_without_fallback_entry() {
+        // An operator-supplied pricing table with no gpt-3.5-turbo entry must
+        // not panic for unknown models; it should charge zero instead.
+        let mut table = HashMap::new();
+        table.insert(
+            "gpt-4".to_string(),
+            pricing::PricingRecord { input: 0.03, output: 0.06, cached_input: None, context_window: Some(8_192) },
+        );
+        let cost = calculate_cost(&table, "unknown-model", 1000, 500);
+        assert_eq!(cost, 0.0);
+    }
+
+    #[test]
+    fn test_metric_model_label_bounds_cardinality() {
+        let pricing = test_pricing();
+        assert_eq!(metric_model_label("gpt-4", &pricing), "gpt-4");
+        assert_eq!(metric_model_label("anything-a-client-sends", &pricing), "other");
+    }
+
+    #[test]
+    fn test_violation_response_names_the_limit() {
+        let violation = validation::Violation::MaxInputChars { limit: 10, actual: 20 };
+        let (status, Json(body)) = violation_response(violation);
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body.limit, Some("max_input_chars"));
+    }
 }
@@ -0,0 +1,124 @@
+//! Prometheus metrics.
+//!
+//! Tracks request volume, per-model token/cost totals, and tokenization
+//! latency so the service can be scraped by a standard monitoring stack.
+//! Metrics live on their own [`prometheus::Registry`] rather than the
+//! crate-global default one, so `/metrics` only ever reports what this
+//! service itself emits.
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+/// Clones cheaply: every field is an `Arc`-backed prometheus handle.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    requests_total: IntCounterVec,
+    tokens_total: IntCounterVec,
+    cost_usd_total: prometheus::CounterVec,
+    tokenize_duration_seconds: HistogramVec,
+    registry: Registry,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let requests_total = IntCounterVec::new(
+            Opts::new("token_counter_requests_total", "Total HTTP requests handled, by route"),
+            &["route"],
+        )
+        .expect("static metric config is valid");
+        let tokens_total = IntCounterVec::new(
+            Opts::new("token_counter_tokens_total", "Total tokens counted, by model"),
+            &["model"],
+        )
+        .expect("static metric config is valid");
+        let cost_usd_total = prometheus::CounterVec::new(
+            Opts::new("token_counter_cost_usd_total", "Total cost in USD, by model"),
+            &["model"],
+        )
+        .expect("static metric config is valid");
+        let tokenize_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "token_counter_tokenize_duration_seconds",
+                "Time spent tokenizing a single request, by backend",
+            ),
+            &["backend"],
+        )
+        .expect("static metric config is valid");
+
+        registry.register(Box::new(requests_total.clone())).expect("metric registered once");
+        registry.register(Box::new(tokens_total.clone())).expect("metric registered once");
+        registry.register(Box::new(cost_usd_total.clone())).expect("metric registered once");
+        registry
+            .register(Box::new(tokenize_duration_seconds.clone()))
+            .expect("metric registered once");
+
+        Self {
+            requests_total,
+            tokens_total,
+            cost_usd_total,
+            tokenize_duration_seconds,
+            registry,
+        }
+    }
+
+    pub fn record_request(&self, route: &str) {
+        self.requests_total.with_label_values(&[route]).inc();
+    }
+
+    /// `model` becomes a label value, so callers must pass a bounded name
+    /// (e.g. via `metric_model_label` in `main`) rather than a raw,
+    /// caller-supplied string — an unbounded label would let a client grow
+    /// this registry's cardinality without limit.
+    pub fn record_tokens(&self, model: &str, tokens: u32) {
+        self.tokens_total.with_label_values(&[model]).inc_by(tokens as u64);
+    }
+
+    /// See [`Metrics::record_tokens`] on bounding the `model` label.
+    pub fn record_cost(&self, model: &str, cost_usd: f64) {
+        self.cost_usd_total.with_label_values(&[model]).inc_by(cost_usd);
+    }
+
+    pub fn observe_tokenize_duration(&self, backend: &str, seconds: f64) {
+        self.tokenize_duration_seconds.with_label_values(&[backend]).observe(seconds);
+    }
+
+    /// Render the current state of every registered metric in the
+    /// Prometheus text exposition format.
+    pub fn gather(&self) -> Vec<u8> {
+        let metric_families = self.registry.gather();
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&metric_families, &mut buffer)
+            .expect("encoding gathered metrics never fails");
+        buffer
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gather_includes_recorded_metrics() {
+        let metrics = Metrics::new();
+        metrics.record_request("/tokens");
+        metrics.record_tokens("gpt-4", 42);
+        metrics.record_cost("gpt-4", 0.01);
+        metrics.observe_tokenize_duration("heuristic", 0.001);
+
+        let output = String::from_utf8(metrics.gather()).unwrap();
+        assert!(output.contains("token_counter_requests_total"));
+        assert!(output.contains("token_counter_tokens_total"));
+        assert!(output.contains("token_counter_cost_usd_total"));
+        assert!(output.contains("token_counter_tokenize_duration_seconds"));
+    }
+}
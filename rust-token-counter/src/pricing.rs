@@ -0,0 +1,174 @@
+//! Hot-reloadable model pricing.
+//!
+//! Pricing used to be a hardcoded table rebuilt on every request. It now
+//! loads once at startup from a TOML or JSON file (`PRICING_FILE`, default
+//! `pricing.toml`) and a background task polls that file for changes,
+//! pushing fresh snapshots through a [`tokio::sync::watch`] channel so
+//! handlers always read the latest rates without a restart or a per-request
+//! allocation.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+const DEFAULT_PRICING_FILE: &str = "pricing.toml";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// USD-per-1K-token pricing for a single model.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PricingRecord {
+    pub input: f64,
+    pub output: f64,
+    #[serde(default)]
+    pub cached_input: Option<f64>,
+    /// Max tokens the model accepts in a single request, used to validate
+    /// a prompt fits before sending it.
+    #[serde(default)]
+    pub context_window: Option<u32>,
+}
+
+/// The full pricing table plus when it was last (re)loaded.
+#[derive(Debug, Clone)]
+pub struct PricingSnapshot {
+    pub models: Arc<HashMap<String, PricingRecord>>,
+    pub last_updated: u64,
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the unix epoch")
+        .as_secs()
+}
+
+fn pricing_file_path() -> PathBuf {
+    std::env::var("PRICING_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_PRICING_FILE))
+}
+
+fn parse_pricing(contents: &str, path: &Path) -> Option<HashMap<String, PricingRecord>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(contents).ok(),
+        _ => toml::from_str(contents).ok(),
+    }
+}
+
+fn load_from_disk(path: &Path) -> Option<HashMap<String, PricingRecord>> {
+    let contents = fs::read_to_string(path).ok()?;
+    parse_pricing(&contents, path)
+}
+
+/// The pricing this service shipped with before `PRICING_FILE` existed,
+/// used as a fallback so the service still boots if the file is missing.
+fn bundled_defaults() -> HashMap<String, PricingRecord> {
+    let mut pricing = HashMap::new();
+
+    // GPT-3.5 Turbo
+    pricing.insert("gpt-3.5-turbo".to_string(), PricingRecord { input: 0.0005, output: 0.0015, cached_input: None, context_window: Some(16_385) });
+    pricing.insert("gpt-3.5-turbo-0125".to_string(), PricingRecord { input: 0.0005, output: 0.0015, cached_input: None, context_window: Some(16_385) });
+    pricing.insert("gpt-3.5-turbo-1106".to_string(), PricingRecord { input: 0.001, output: 0.002, cached_input: None, context_window: Some(16_385) });
+
+    // GPT-4
+    pricing.insert("gpt-4".to_string(), PricingRecord { input: 0.03, output: 0.06, cached_input: None, context_window: Some(8_192) });
+    pricing.insert("gpt-4-0613".to_string(), PricingRecord { input: 0.03, output: 0.06, cached_input: None, context_window: Some(8_192) });
+
+    // GPT-4 Turbo
+    pricing.insert("gpt-4-turbo".to_string(), PricingRecord { input: 0.01, output: 0.03, cached_input: None, context_window: Some(128_000) });
+    pricing.insert("gpt-4-turbo-preview".to_string(), PricingRecord { input: 0.01, output: 0.03, cached_input: None, context_window: Some(128_000) });
+    pricing.insert("gpt-4-1106-preview".to_string(), PricingRecord { input: 0.01, output: 0.03, cached_input: None, context_window: Some(128_000) });
+
+    // GPT-4o
+    pricing.insert("gpt-4o".to_string(), PricingRecord { input: 0.005, output: 0.015, cached_input: None, context_window: Some(128_000) });
+    pricing.insert("gpt-4o-mini".to_string(), PricingRecord { input: 0.00015, output: 0.0006, cached_input: None, context_window: Some(128_000) });
+
+    // Claude models (for future provider support)
+    pricing.insert("claude-3-opus".to_string(), PricingRecord { input: 0.015, output: 0.075, cached_input: None, context_window: Some(200_000) });
+    pricing.insert("claude-3-sonnet".to_string(), PricingRecord { input: 0.003, output: 0.015, cached_input: None, context_window: Some(200_000) });
+    pricing.insert("claude-3-haiku".to_string(), PricingRecord { input: 0.00025, output: 0.00125, cached_input: None, context_window: Some(200_000) });
+
+    pricing
+}
+
+/// Load the pricing table once at startup, falling back to the bundled
+/// defaults if `PRICING_FILE` is missing or fails to parse.
+pub fn load_initial() -> PricingSnapshot {
+    let path = pricing_file_path();
+    let models = load_from_disk(&path).unwrap_or_else(|| {
+        warn!(path = %path.display(), "pricing file not found or invalid, using bundled defaults");
+        bundled_defaults()
+    });
+    PricingSnapshot {
+        models: Arc::new(models),
+        last_updated: now_unix(),
+    }
+}
+
+/// Spawn a background task that polls the pricing file's mtime and pushes a
+/// fresh snapshot through `tx` whenever it changes.
+pub fn spawn_watcher(tx: watch::Sender<PricingSnapshot>) {
+    tokio::spawn(async move {
+        let path = pricing_file_path();
+        let mut last_modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            let Ok(modified) = fs::metadata(&path).and_then(|m| m.modified()) else {
+                continue;
+            };
+            if Some(modified) == last_modified {
+                continue;
+            }
+            last_modified = Some(modified);
+
+            if let Some(models) = load_from_disk(&path) {
+                info!(path = %path.display(), "reloaded pricing table");
+                let _ = tx.send(PricingSnapshot {
+                    models: Arc::new(models),
+                    last_updated: now_unix(),
+                });
+            } else {
+                warn!(path = %path.display(), "pricing file changed but failed to parse, keeping old rates");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pricing_reads_toml() {
+        let toml_src = r#"
+            [gpt-4]
+            input = 0.03
+            output = 0.06
+        "#;
+        let parsed = parse_pricing(toml_src, Path::new("pricing.toml")).unwrap();
+        assert_eq!(parsed["gpt-4"].input, 0.03);
+        assert_eq!(parsed["gpt-4"].output, 0.06);
+        assert!(parsed["gpt-4"].cached_input.is_none());
+    }
+
+    #[test]
+    fn parse_pricing_reads_json() {
+        let json_src = r#"{"gpt-4": {"input": 0.03, "output": 0.06, "cached_input": 0.015}}"#;
+        let parsed = parse_pricing(json_src, Path::new("pricing.json")).unwrap();
+        assert_eq!(parsed["gpt-4"].cached_input, Some(0.015));
+    }
+
+    #[test]
+    fn bundled_defaults_cover_known_models() {
+        let defaults = bundled_defaults();
+        assert!(defaults.contains_key("gpt-3.5-turbo"));
+        assert!(defaults.contains_key("gpt-4o"));
+    }
+}
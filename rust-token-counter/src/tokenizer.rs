@@ -0,0 +1,247 @@
+//! Tokenizer backends.
+//!
+//! [`HeuristicTokenizer`] is the original ~4-chars-per-token approximation,
+//! kept as a zero-dependency fast path. [`ExactTokenizer`] wraps `tiktoken-rs`
+//! for OpenAI models and [`HfTokenizer`] wraps the HuggingFace `tokenizers`
+//! crate (via a `tokenizer.json` on disk) for everything else, giving callers a real count
+//! instead of an estimate whenever an encoding is available.
+
+use std::env;
+use std::sync::Arc;
+
+use serde::Serialize;
+use tiktoken_rs::CoreBPE;
+
+/// Which tokenizer produced a count, surfaced to callers so they know
+/// whether a result is exact or approximate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenizerBackend {
+    Heuristic,
+    Exact,
+}
+
+impl TokenizerBackend {
+    /// Parse the `TOKENIZER_BACKEND` env var / `backend` query param.
+    /// Unrecognized or missing values fall back to `Heuristic`.
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "exact" => TokenizerBackend::Exact,
+            _ => TokenizerBackend::Heuristic,
+        }
+    }
+
+    /// The backend requested at startup via `TOKENIZER_BACKEND`, defaulting
+    /// to `heuristic` when unset.
+    pub fn from_env() -> Self {
+        env::var("TOKENIZER_BACKEND")
+            .map(|v| Self::parse(&v))
+            .unwrap_or(TokenizerBackend::Heuristic)
+    }
+
+    /// Label used in logs and metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenizerBackend::Heuristic => "heuristic",
+            TokenizerBackend::Exact => "exact",
+        }
+    }
+}
+
+/// A pluggable token counter. Implementations may be exact (tokenizer-backed)
+/// or approximate (character heuristic).
+pub trait Tokenizer: Send + Sync {
+    fn count(&self, text: &str) -> u32;
+    fn backend(&self) -> TokenizerBackend;
+}
+
+/// Character-based estimation: ~4 chars per token for English, calibrated
+/// against OpenAI's tiktoken. This is an approximation—actual token counts
+/// may vary by ±10% depending on content.
+pub struct HeuristicTokenizer;
+
+impl Tokenizer for HeuristicTokenizer {
+    fn count(&self, text: &str) -> u32 {
+        if text.is_empty() {
+            return 0;
+        }
+
+        // Character-based estimation: ~4 chars per token for English
+        // Add small buffer for special tokens and edge cases
+        let char_count = text.chars().count();
+        let base_estimate = (char_count as f64 / 4.0).ceil() as u32;
+
+        // Account for whitespace density (more spaces = slightly more tokens)
+        let whitespace_count = text.chars().filter(|c| c.is_whitespace()).count();
+        let whitespace_factor = 1.0 + (whitespace_count as f64 / char_count as f64) * 0.1;
+
+        ((base_estimate as f64) * whitespace_factor).ceil() as u32
+    }
+
+    fn backend(&self) -> TokenizerBackend {
+        TokenizerBackend::Heuristic
+    }
+}
+
+/// Exact counts via a `tiktoken-rs` byte-pair encoding (cl100k_base,
+/// o200k_base, ...).
+pub struct ExactTokenizer {
+    bpe: Arc<CoreBPE>,
+}
+
+impl Tokenizer for ExactTokenizer {
+    fn count(&self, text: &str) -> u32 {
+        // `encode_ordinary` matches tiktoken's own `num_tokens_from_messages`
+        // semantics: special-token substrings in user content (e.g.
+        // `<|endoftext|>`) are counted as ordinary text, not collapsed into a
+        // single special token, which would under-count on this
+        // billing-accurate path.
+        self.bpe.encode_ordinary(text).len() as u32
+    }
+
+    fn backend(&self) -> TokenizerBackend {
+        TokenizerBackend::Exact
+    }
+}
+
+/// Exact counts via a HuggingFace `tokenizers::Tokenizer` loaded from a local
+/// `tokenizer.json`, for non-OpenAI models that don't have a tiktoken
+/// encoding.
+pub struct HfTokenizer {
+    inner: tokenizers::Tokenizer,
+}
+
+impl Tokenizer for HfTokenizer {
+    fn count(&self, text: &str) -> u32 {
+        self.inner
+            .encode(text, false)
+            .map(|enc| enc.len() as u32)
+            .unwrap_or(0)
+    }
+
+    fn backend(&self) -> TokenizerBackend {
+        TokenizerBackend::Exact
+    }
+}
+
+/// Map an OpenAI model name to its tiktoken encoding, mirroring tiktoken's
+/// own `MODEL_TO_ENCODING` table for the models this service prices.
+fn encoding_for_model(model: &str) -> Option<&'static str> {
+    if model.starts_with("gpt-4o") {
+        Some("o200k_base")
+    } else if model.starts_with("gpt-4") || model.starts_with("gpt-3.5") {
+        Some("cl100k_base")
+    } else {
+        None
+    }
+}
+
+/// Resolve the tokenizer to use for `model` given the requested backend.
+///
+/// `TokenizerBackend::Exact` falls back to the heuristic when no tiktoken
+/// encoding is registered for `model` and no `TOKENIZER_JSON_PATH` is
+/// configured (e.g. Claude models), so callers always get a count back.
+///
+/// The tiktoken encodings are process-wide singletons (`*_singleton()`):
+/// the BPE merge table is parsed once and shared as an `Arc` from then on,
+/// so a hot path like `/tokens/batch` never pays the full load cost per call.
+pub fn resolve(model: &str, requested: TokenizerBackend) -> Box<dyn Tokenizer> {
+    if requested == TokenizerBackend::Heuristic {
+        return Box::new(HeuristicTokenizer);
+    }
+
+    match encoding_for_model(model) {
+        Some("o200k_base") => {
+            Box::new(ExactTokenizer { bpe: tiktoken_rs::o200k_base_singleton() })
+        }
+        Some(_cl100k) => {
+            Box::new(ExactTokenizer { bpe: tiktoken_rs::cl100k_base_singleton() })
+        }
+        None => resolve_hf().unwrap_or_else(|| Box::new(HeuristicTokenizer)),
+    }
+}
+
+/// Load a HuggingFace tokenizer from `TOKENIZER_JSON_PATH`, if configured.
+fn resolve_hf() -> Option<Box<dyn Tokenizer>> {
+    let path = env::var("TOKENIZER_JSON_PATH").ok()?;
+    let inner = tokenizers::Tokenizer::from_file(&path).ok()?;
+    Some(Box::new(HfTokenizer { inner }))
+}
+
+/// Spawn a task that eagerly warms the tiktoken encodings this service
+/// uses, flipping `tx` to `true` once they're loaded so `/ready` can gate
+/// traffic until the exact backend is actually usable.
+///
+/// This warms the same process-wide singletons `resolve` reads
+/// (`*_singleton()`), not a throwaway `CoreBPE` built and dropped here —
+/// otherwise `/ready` would report a warm cache while the first real
+/// request still pays the full load cost. A `TOKENIZER_JSON_PATH`
+/// tokenizer is not cached: it is loaded here once and dropped, and
+/// `resolve` loads it from disk again on every call that needs it.
+pub fn spawn_encoding_loader(tx: tokio::sync::watch::Sender<bool>) {
+    tokio::spawn(async move {
+        // tiktoken's BPE tables and an on-disk tokenizer.json are both
+        // blocking loads, so do them off the async executor.
+        let _ = tokio::task::spawn_blocking(|| {
+            let _ = tiktoken_rs::cl100k_base_singleton();
+            let _ = tiktoken_rs::o200k_base_singleton();
+            let _ = resolve_hf();
+        })
+        .await;
+
+        let _ = tx.send(true);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heuristic_empty() {
+        assert_eq!(HeuristicTokenizer.count(""), 0);
+    }
+
+    #[test]
+    fn heuristic_short() {
+        // "Hello" = 5 chars, ~1-2 tokens
+        let tokens = HeuristicTokenizer.count("Hello");
+        assert!(tokens >= 1 && tokens <= 3);
+    }
+
+    #[test]
+    fn heuristic_sentence() {
+        // "The quick brown fox jumps over the lazy dog" = 43 chars, ~9-11 tokens
+        let tokens = HeuristicTokenizer.count("The quick brown fox jumps over the lazy dog");
+        assert!(tokens >= 8 && tokens <= 15);
+    }
+
+    #[test]
+    fn backend_parse_defaults_to_heuristic() {
+        assert_eq!(TokenizerBackend::parse("nonsense"), TokenizerBackend::Heuristic);
+        assert_eq!(TokenizerBackend::parse("exact"), TokenizerBackend::Exact);
+    }
+
+    #[test]
+    fn encoding_for_model_picks_o200k_for_gpt4o() {
+        assert_eq!(encoding_for_model("gpt-4o-mini"), Some("o200k_base"));
+        assert_eq!(encoding_for_model("gpt-4-turbo"), Some("cl100k_base"));
+        assert_eq!(encoding_for_model("claude-3-opus"), None);
+    }
+
+    #[test]
+    fn resolve_heuristic_backend_never_touches_tiktoken() {
+        let tok = resolve("gpt-4o", TokenizerBackend::Heuristic);
+        assert_eq!(tok.backend(), TokenizerBackend::Heuristic);
+    }
+
+    #[test]
+    fn cl100k_singleton_is_shared_across_calls() {
+        // `resolve` leans on tiktoken's process-wide singleton rather than
+        // rebuilding the merge table per call; confirm the singleton itself
+        // hands back the same `Arc` every time.
+        let first = tiktoken_rs::cl100k_base_singleton();
+        let second = tiktoken_rs::cl100k_base_singleton();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+}
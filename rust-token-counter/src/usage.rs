@@ -0,0 +1,160 @@
+//! Usage accounting.
+//!
+//! Tracks cumulative token and cost usage per API key so the service can
+//! back quota enforcement and billing dashboards, not just one-shot
+//! calculations. Usage is bucketed into rolling billing periods
+//! (`BILLING_PERIOD_SECONDS`, default 30 days) that reset once the window
+//! elapses. This is in-memory only and does not survive a restart.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+const DEFAULT_BILLING_PERIOD_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+fn billing_period_seconds() -> u64 {
+    env::var("BILLING_PERIOD_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_BILLING_PERIOD_SECONDS)
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the unix epoch")
+        .as_secs()
+}
+
+/// Accumulated usage for a single API key within the current billing period.
+#[derive(Debug, Clone, Default)]
+struct UsageAccumulator {
+    tokens_in: u64,
+    tokens_out: u64,
+    cost_usd: f64,
+    period_start: u64,
+}
+
+impl UsageAccumulator {
+    fn new_period(now: u64) -> Self {
+        Self {
+            period_start: now,
+            ..Default::default()
+        }
+    }
+
+    /// Reset the accumulator if the current billing period has elapsed.
+    fn roll_if_expired(&mut self, now: u64) {
+        if now.saturating_sub(self.period_start) >= billing_period_seconds() {
+            *self = Self::new_period(now);
+        }
+    }
+}
+
+/// A point-in-time snapshot of a key's usage for the current billing period.
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageSummary {
+    pub api_key: String,
+    pub tokens_in: u64,
+    pub tokens_out: u64,
+    pub cost_usd: f64,
+    pub period_start: u64,
+}
+
+/// Shared, thread-safe store of per-key usage accumulators, cheap to clone
+/// into axum's router state.
+#[derive(Debug, Clone, Default)]
+pub struct UsageStore {
+    keys: Arc<RwLock<HashMap<String, UsageAccumulator>>>,
+}
+
+impl UsageStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a `/tokens` or `/cost` call against `api_key`, rolling the
+    /// billing period over first if it has elapsed.
+    pub fn record(&self, api_key: &str, tokens_in: u32, tokens_out: u32, cost_usd: f64) {
+        let now = now_unix();
+        let mut keys = self.keys.write().unwrap();
+        let acc = keys
+            .entry(api_key.to_string())
+            .or_insert_with(|| UsageAccumulator::new_period(now));
+        acc.roll_if_expired(now);
+        acc.tokens_in += tokens_in as u64;
+        acc.tokens_out += tokens_out as u64;
+        acc.cost_usd += cost_usd;
+    }
+
+    /// Current-period usage for a single key, or `None` if it has never
+    /// been recorded.
+    pub fn get(&self, api_key: &str) -> Option<UsageSummary> {
+        let now = now_unix();
+        let mut keys = self.keys.write().unwrap();
+        let acc = keys.get_mut(api_key)?;
+        acc.roll_if_expired(now);
+        Some(UsageSummary {
+            api_key: api_key.to_string(),
+            tokens_in: acc.tokens_in,
+            tokens_out: acc.tokens_out,
+            cost_usd: acc.cost_usd,
+            period_start: acc.period_start,
+        })
+    }
+
+    /// Current-period usage for every key that has ever been recorded.
+    pub fn all(&self) -> Vec<UsageSummary> {
+        let now = now_unix();
+        let mut keys = self.keys.write().unwrap();
+        keys.iter_mut()
+            .map(|(api_key, acc)| {
+                acc.roll_if_expired(now);
+                UsageSummary {
+                    api_key: api_key.clone(),
+                    tokens_in: acc.tokens_in,
+                    tokens_out: acc.tokens_out,
+                    cost_usd: acc.cost_usd,
+                    period_start: acc.period_start,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_accumulates_across_calls() {
+        let store = UsageStore::new();
+        store.record("key-a", 100, 50, 0.01);
+        store.record("key-a", 10, 5, 0.001);
+
+        let summary = store.get("key-a").unwrap();
+        assert_eq!(summary.tokens_in, 110);
+        assert_eq!(summary.tokens_out, 55);
+        assert!((summary.cost_usd - 0.011).abs() < 1e-9);
+    }
+
+    #[test]
+    fn get_returns_none_for_unknown_key() {
+        let store = UsageStore::new();
+        assert!(store.get("missing").is_none());
+    }
+
+    #[test]
+    fn all_includes_every_recorded_key() {
+        let store = UsageStore::new();
+        store.record("key-a", 1, 1, 0.0);
+        store.record("key-b", 2, 2, 0.0);
+
+        let mut keys: Vec<_> = store.all().into_iter().map(|s| s.api_key).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["key-a".to_string(), "key-b".to_string()]);
+    }
+}
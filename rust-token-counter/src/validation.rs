@@ -0,0 +1,172 @@
+//! Configurable input validation.
+//!
+//! `MAX_INPUT_CHARS` and `MAX_INPUT_TOKENS` bound how much text a single
+//! request may submit. When a request exceeds a limit the caller either
+//! opts into truncation (`truncate: true`), in which case only the
+//! truncated prefix is priced, or gets a `413` naming the limit it hit.
+
+use std::env;
+
+use crate::tokenizer::{self, TokenizerBackend};
+
+/// Configured input limits, read fresh per request since they're cheap env
+/// lookups and operators may want to tune them without a restart.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_chars: Option<usize>,
+    pub max_tokens: Option<u32>,
+}
+
+impl Limits {
+    pub fn from_env() -> Self {
+        Self {
+            max_chars: env::var("MAX_INPUT_CHARS").ok().and_then(|v| v.parse().ok()),
+            max_tokens: env::var("MAX_INPUT_TOKENS").ok().and_then(|v| v.parse().ok()),
+        }
+    }
+}
+
+/// Input accepted by the configured limits, truncated if necessary.
+#[derive(Debug)]
+pub struct Validated {
+    pub text: String,
+    pub truncated: bool,
+    pub chars_dropped: usize,
+}
+
+/// The limit that rejected a request which wasn't allowed to truncate.
+#[derive(Debug)]
+pub enum Violation {
+    MaxInputChars { limit: usize, actual: usize },
+    MaxInputTokens { limit: u32, actual: u32 },
+}
+
+impl Violation {
+    pub fn limit_name(&self) -> &'static str {
+        match self {
+            Violation::MaxInputChars { .. } => "max_input_chars",
+            Violation::MaxInputTokens { .. } => "max_input_tokens",
+        }
+    }
+
+    pub fn limit_value(&self) -> u64 {
+        match self {
+            Violation::MaxInputChars { limit, .. } => *limit as u64,
+            Violation::MaxInputTokens { limit, .. } => *limit as u64,
+        }
+    }
+
+    pub fn actual(&self) -> u64 {
+        match self {
+            Violation::MaxInputChars { actual, .. } => *actual as u64,
+            Violation::MaxInputTokens { actual, .. } => *actual as u64,
+        }
+    }
+}
+
+/// Validate (and, if `truncate` is set, shrink) `text` against `limits`.
+pub fn validate(
+    text: &str,
+    limits: Limits,
+    truncate: bool,
+    model: &str,
+    backend: TokenizerBackend,
+) -> Result<Validated, Violation> {
+    let mut working = text.to_string();
+    let mut chars_dropped = 0usize;
+    let mut truncated = false;
+
+    if let Some(max_chars) = limits.max_chars {
+        let char_count = working.chars().count();
+        if char_count > max_chars {
+            if !truncate {
+                return Err(Violation::MaxInputChars { limit: max_chars, actual: char_count });
+            }
+            working = working.chars().take(max_chars).collect();
+            chars_dropped += char_count - max_chars;
+            truncated = true;
+        }
+    }
+
+    if let Some(max_tokens) = limits.max_tokens {
+        let token_count = tokenizer::resolve(model, backend).count(&working);
+        if token_count > max_tokens {
+            if !truncate {
+                return Err(Violation::MaxInputTokens { limit: max_tokens, actual: token_count });
+            }
+            let (shrunk, dropped) = shrink_to_token_limit(working, max_tokens, model, backend);
+            working = shrunk;
+            chars_dropped += dropped;
+            truncated = true;
+        }
+    }
+
+    Ok(Validated { text: working, truncated, chars_dropped })
+}
+
+/// Repeatedly drop a proportional slice of trailing characters until `text`
+/// fits within `max_tokens`, returning the shrunk text and chars dropped.
+fn shrink_to_token_limit(
+    mut text: String,
+    max_tokens: u32,
+    model: &str,
+    backend: TokenizerBackend,
+) -> (String, usize) {
+    let original_len = text.chars().count();
+    let tok = tokenizer::resolve(model, backend);
+
+    while !text.is_empty() {
+        let count = tok.count(&text);
+        if count <= max_tokens {
+            break;
+        }
+        let ratio = max_tokens as f64 / count as f64;
+        let current_len = text.chars().count();
+        let new_len = ((current_len as f64) * ratio).floor() as usize;
+        let new_len = new_len.min(current_len.saturating_sub(1));
+        text = text.chars().take(new_len).collect();
+    }
+
+    (text, original_len - text.chars().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn within_limits_is_unchanged() {
+        let limits = Limits { max_chars: Some(100), max_tokens: Some(100) };
+        let result = validate("hello", limits, false, "gpt-3.5-turbo", TokenizerBackend::Heuristic).unwrap();
+        assert_eq!(result.text, "hello");
+        assert!(!result.truncated);
+        assert_eq!(result.chars_dropped, 0);
+    }
+
+    #[test]
+    fn rejects_when_truncate_not_set() {
+        let limits = Limits { max_chars: Some(3), max_tokens: None };
+        let err = validate("hello", limits, false, "gpt-3.5-turbo", TokenizerBackend::Heuristic).unwrap_err();
+        assert_eq!(err.limit_name(), "max_input_chars");
+    }
+
+    #[test]
+    fn truncates_when_requested() {
+        let limits = Limits { max_chars: Some(3), max_tokens: None };
+        let result = validate("hello", limits, true, "gpt-3.5-turbo", TokenizerBackend::Heuristic).unwrap();
+        assert_eq!(result.text, "hel");
+        assert!(result.truncated);
+        assert_eq!(result.chars_dropped, 2);
+    }
+
+    #[test]
+    fn shrinks_to_token_limit() {
+        let limits = Limits { max_chars: None, max_tokens: Some(2) };
+        let long_text = "word ".repeat(50);
+        let result = validate(&long_text, limits, true, "gpt-3.5-turbo", TokenizerBackend::Heuristic).unwrap();
+        assert!(result.truncated);
+        let final_tokens =
+            tokenizer::resolve("gpt-3.5-turbo", TokenizerBackend::Heuristic).count(&result.text);
+        assert!(final_tokens <= 2);
+    }
+}